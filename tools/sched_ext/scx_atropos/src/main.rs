@@ -44,9 +44,15 @@ use ordered_float::OrderedFloat;
 /// chiplet in a six-chiplet AMD processor, and could match the performance of
 /// production setup using CFS.
 ///
-/// WARNING: Atropos currently assumes that all domains have equal
-/// processing power and at similar distances from each other. This
-/// limitation will be removed in the future.
+/// CPU -> domain assignment (cpu_dom_id_map/dom_cpumasks) is fixed at startup
+/// in BPF rodata and frozen once the program loads, so CPU hotplug support is
+/// balancer-accounting-only: the userspace load balancer stops pulling load
+/// into a domain whose CPUs have all gone offline and drains the load still
+/// attributed to it, and judges a domain that's lost some CPUs by the
+/// capacity of the ones left online, but the BPF dispatch path
+/// itself doesn't learn about hotplug and can still target a CPU that's since
+/// gone offline, and a CPU onlined after startup is never picked up for
+/// dispatch. Restart the scheduler to pick up a changed CPU set.
 #[derive(Debug, Parser)]
 struct Opts {
     /// Scheduling slice duration in microseconds.
@@ -82,20 +88,78 @@ struct Opts {
     #[clap(short = 'g', long, default_value = "1")]
     greedy_threshold: u32,
 
-    /// The load decay factor. Every interval, the existing load is decayed
-    /// by this factor and new load is added. Must be in the range [0.0,
-    /// 0.99]. The smaller the value, the more sensitive load calculation
-    /// is to recent changes. When 0.0, history is ignored and the load
-    /// value from the latest period is used directly.
-    #[clap(long, default_value = "0.5")]
-    load_decay_factor: f64,
-
     /// Disable load balancing. Unless disabled, periodically userspace will
     /// calculate the load factor of each domain and instruct BPF which
     /// processes to move.
     #[clap(long, action = clap::ArgAction::SetTrue)]
     no_load_balance: bool,
 
+    /// When pairing an overloaded domain with one to pull tasks from,
+    /// prefer domains within this NUMA distance (as reported by
+    /// /sys/devices/system/node/nodeX/distance) before considering
+    /// farther ones. A same-node distance is normally 10, so the default
+    /// tries same-node domains first.
+    #[clap(long, default_value = "10")]
+    max_migration_distance: u32,
+
+    /// Cost charged against a task that was just migrated. For this many
+    /// subsequent load-balance intervals (rounded up), migrating the task
+    /// again requires the pre-migration imbalance to be at least this many
+    /// more times the post-migration imbalance, added on top of the
+    /// per-BalanceLevel cost factor a migration already has to clear; a task
+    /// outside its protection window, or one that's never migrated, is
+    /// unaffected. 0 disables the protection window entirely. Raise this
+    /// if tasks are observed bouncing back and forth between domains
+    /// across consecutive intervals.
+    #[clap(long, default_value = "1.0")]
+    migration_cost: f64,
+
+    /// Floor on a task's computed load, in the same [0, 1024] scale as the
+    /// kernel's uclamp_min. Tasks matched by no --uclamp-config line (every
+    /// task when no config is given) are accounted as at least this heavy, so
+    /// the balancer is more reluctant to stack latency-sensitive work.
+    #[clap(long, default_value = "0")]
+    uclamp_min: u32,
+
+    /// Ceiling on a task's computed load, in the same [0, 1024] scale as the
+    /// kernel's uclamp_max. Caps how heavy background work can be accounted,
+    /// even if its actual runtime would otherwise push it higher.
+    #[clap(long, default_value = "1024")]
+    uclamp_max: u32,
+
+    /// Path to a uclamp config file. Each non-empty, non-comment line is
+    /// "PATTERN UTIL_MIN UTIL_MAX", where PATTERN is a pid (1234), a pid
+    /// range (1000-2000) or a substring matched against /proc/PID/comm.
+    /// The first matching line wins; tasks matching none fall back to
+    /// --uclamp-min/--uclamp-max.
+    #[clap(long)]
+    uclamp_config: Option<String>,
+
+    /// Enable energy-aware consolidation: when the machine is idle enough,
+    /// actively pack load onto a subset of domains instead of equalizing
+    /// load across all of them, so the rest can reach deeper idle states.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    energy_aware: bool,
+
+    /// Under --energy-aware, a recipient domain is offered room for load
+    /// only up to this normalized utilization (load / capacity). A single
+    /// migrated task larger than the remaining room still takes the
+    /// recipient past it.
+    #[clap(long, default_value = "0.85")]
+    energy_util_cap: f64,
+
+    /// Under --energy-aware, start consolidating once aggregate machine
+    /// utilization drops below this fraction.
+    #[clap(long, default_value = "0.30")]
+    energy_aware_enter_under: f64,
+
+    /// Under --energy-aware, stop consolidating once aggregate machine
+    /// utilization rises above this fraction. Kept above
+    /// --energy-aware-enter-under to provide hysteresis so domains don't
+    /// flap between consolidated and spread states.
+    #[clap(long, default_value = "0.50")]
+    energy_aware_exit_over: f64,
+
     /// Put per-cpu kthreads directly into local dsq's.
     #[clap(short = 'k', long, action = clap::ArgAction::SetTrue)]
     kthreads_local: bool,
@@ -143,6 +207,88 @@ fn now_monotonic() -> u64 {
     time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
 }
 
+// PELT (Per-Entity Load Tracking), mirroring the kernel's CFS runnable-load
+// accounting (kernel/sched/pelt.c). Load is tracked as a geometric series
+// of ~1ms periods, decayed by PELT_DECAY each period, so y^32 ~= 0.5: a
+// period's contribution roughly halves every 32ms. A fully-runnable period
+// contributes PELT_PERIOD_CONTRIB to load_sum. PELT_LOAD_AVG_MAX is the
+// converged sum of the infinite series and is what a fully-runnable
+// task's load_sum asymptotically approaches.
+const PELT_PERIOD_NS: u64 = 1_000_000;
+const PELT_PERIOD_CONTRIB: f64 = 1024.0;
+const PELT_DECAY: f64 = 0.978_572_062_5;
+const PELT_LOAD_AVG_MAX: f64 = 47_742.0;
+
+// y^n for small n, so decaying by a handful of periods is a table lookup
+// rather than a powf() call; larger gaps (e.g. a task that went unseen for
+// a while) fall back to powi().
+const PELT_DECAY_TABLE_LEN: usize = 64;
+
+fn pelt_decay_table() -> [f64; PELT_DECAY_TABLE_LEN + 1] {
+    let mut table = [1.0f64; PELT_DECAY_TABLE_LEN + 1];
+    for i in 1..=PELT_DECAY_TABLE_LEN {
+        table[i] = table[i - 1] * PELT_DECAY;
+    }
+    table
+}
+
+fn pelt_decay_pow(periods: u64, table: &[f64; PELT_DECAY_TABLE_LEN + 1]) -> f64 {
+    match usize::try_from(periods) {
+        Ok(n) if n <= PELT_DECAY_TABLE_LEN => table[n],
+        _ => PELT_DECAY.powi(periods.min(i32::MAX as u64) as i32),
+    }
+}
+
+// Advance a task's PELT state by @dt nanoseconds, @active_ns of which the
+// task was runnable, decomposing @dt into whatever's left of the in-flight
+// ~1ms period, zero or more whole periods, and the partial period that
+// follows. Returns the updated (load_sum, period_contrib_ns).
+fn pelt_accumulate(
+    load_sum: f64,
+    period_contrib_ns: u64,
+    dt: u64,
+    active_ns: u64,
+    table: &[f64; PELT_DECAY_TABLE_LEN + 1],
+) -> (f64, u64) {
+    if dt == 0 {
+        return (load_sum, period_contrib_ns);
+    }
+
+    // We don't know exactly when within @dt the task was runnable, only
+    // how much of it was, so treat @active_ns as spread uniformly across
+    // @dt. This is the same assumption the flat per-interval load made,
+    // just applied per period instead of per load-balance round.
+    let util = (active_ns as f64 / dt as f64).clamp(0.0, 1.0);
+    // Runnable ns -> load_sum units.
+    let scale = PELT_PERIOD_CONTRIB / PELT_PERIOD_NS as f64;
+
+    let total_ns = period_contrib_ns + dt;
+    let full_periods = total_ns / PELT_PERIOD_NS;
+    let new_contrib_ns = total_ns % PELT_PERIOD_NS;
+
+    if full_periods == 0 {
+        // Still inside the same period as last time: no boundary crossed,
+        // nothing to decay yet.
+        return (load_sum + util * dt as f64 * scale, new_contrib_ns);
+    }
+
+    // At least one period boundary was crossed. The old sum, which already
+    // holds the earlier part of the in-flight period, decays by y once per
+    // period crossed. The rest of that in-flight period (d1) is now
+    // full_periods periods in the past and is weighted y^full_periods. The
+    // full_periods - 1 whole periods after it are weighted y^1..
+    // y^(full_periods - 1), collapsed into one geometric-sum term instead
+    // of iterating period by period. The new, still-open new_contrib_ns
+    // hasn't completed a period yet and so isn't decayed at all.
+    let decay_n = pelt_decay_pow(full_periods, table);
+    let d1 = (PELT_PERIOD_NS - period_contrib_ns) as f64;
+    let geo_sum = PELT_DECAY * (1.0 - decay_n) / (1.0 - PELT_DECAY) - decay_n;
+    let load_sum = load_sum * decay_n
+        + util * scale * (d1 * decay_n + PELT_PERIOD_NS as f64 * geo_sum + new_contrib_ns as f64);
+
+    (load_sum, new_contrib_ns)
+}
+
 fn clear_map(map: &mut libbpf_rs::Map) {
     // XXX: libbpf_rs has some design flaw that make it impossible to
     // delete while iterating despite it being safe so we alias it here
@@ -234,15 +380,368 @@ impl MyProcStat {
     }
 }
 
+// Per-task utilization clamp, analogous to the kernel's uclamp_min/max,
+// expressed on the same [0, UCLAMP_MAX] scale as task weight-derived load.
+const UCLAMP_MAX: u32 = 1024;
+
+fn read_comm(pid: i32) -> Result<String> {
+    let path = format!("/proc/{}/comm", pid);
+    Ok(std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to open {:?}", &path))?
+        .trim()
+        .to_string())
+}
+
+#[derive(Debug, Clone)]
+enum UclampPattern {
+    Pid(i32),
+    PidRange(i32, i32),
+    Comm(String),
+}
+
+#[derive(Debug, Clone)]
+struct UclampRule {
+    pattern: UclampPattern,
+    util_min: u32,
+    util_max: u32,
+}
+
+// Per-task util_min/util_max, consulted by LoadBalancer::read_task_loads to
+// floor/ceil the load a task is accounted as contributing. Rules come from
+// an optional config file and are consulted in order, first match wins;
+// tasks matching nothing fall back to the --uclamp-min/--uclamp-max
+// defaults.
+#[derive(Debug, Clone)]
+struct UclampConfig {
+    default_min: u32,
+    default_max: u32,
+    rules: Vec<UclampRule>,
+}
+
+impl UclampConfig {
+    fn new(default_min: u32, default_max: u32, config_path: Option<&str>) -> Result<Self> {
+        let rules = match config_path {
+            Some(path) => Self::parse(path)?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            default_min: default_min.min(UCLAMP_MAX),
+            default_max: default_max.min(UCLAMP_MAX).max(default_min.min(UCLAMP_MAX)),
+            rules,
+        })
+    }
+
+    fn parse(path: &str) -> Result<Vec<UclampRule>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to open uclamp config {:?}", path))?;
+        let mut rules = Vec::new();
+
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut toks = line.split_whitespace();
+            let pattern_str = toks
+                .next()
+                .ok_or_else(|| anyhow!("{}:{}: missing pattern", path, lineno + 1))?;
+            let util_min = toks
+                .next()
+                .ok_or_else(|| anyhow!("{}:{}: missing util_min", path, lineno + 1))?
+                .parse::<u32>()
+                .with_context(|| format!("{}:{}: invalid util_min", path, lineno + 1))?;
+            let util_max = toks
+                .next()
+                .ok_or_else(|| anyhow!("{}:{}: missing util_max", path, lineno + 1))?
+                .parse::<u32>()
+                .with_context(|| format!("{}:{}: invalid util_max", path, lineno + 1))?;
+
+            let pattern = match pattern_str.split_once('-') {
+                Some((lo, hi)) if lo.parse::<i32>().is_ok() && hi.parse::<i32>().is_ok() => {
+                    UclampPattern::PidRange(lo.parse().unwrap(), hi.parse().unwrap())
+                }
+                _ => match pattern_str.parse::<i32>() {
+                    Ok(pid) => UclampPattern::Pid(pid),
+                    Err(_) => UclampPattern::Comm(pattern_str.to_string()),
+                },
+            };
+
+            rules.push(UclampRule {
+                pattern,
+                util_min: util_min.min(UCLAMP_MAX),
+                util_max: util_max.min(UCLAMP_MAX).max(util_min.min(UCLAMP_MAX)),
+            });
+        }
+
+        Ok(rules)
+    }
+
+    // Returns the effective (util_min, util_max) for @pid.
+    fn lookup(&self, pid: i32) -> (u32, u32) {
+        for rule in &self.rules {
+            let matched = match &rule.pattern {
+                UclampPattern::Pid(p) => *p == pid,
+                UclampPattern::PidRange(lo, hi) => pid >= *lo && pid <= *hi,
+                UclampPattern::Comm(pat) => {
+                    read_comm(pid).map_or(false, |comm| comm.contains(pat.as_str()))
+                }
+            };
+            if matched {
+                return (rule.util_min, rule.util_max);
+            }
+        }
+        (self.default_min, self.default_max)
+    }
+}
+
+// Arch-scaled CPU capacity is a 0-1024 value read from
+// /sys/devices/system/cpu/cpuX/cpu_capacity. Symmetric systems (e.g. most
+// x86 machines) don't expose this file at all, in which case fall back to
+// cpufreq's max frequency, which at least captures big.LITTLE-style
+// frequency asymmetry even without an arch-topology capacity value. If
+// neither is available, every CPU is assumed to have the max capacity.
+const CPU_CAPACITY_MAX: u32 = 1024;
+
+fn read_cpu_capacity_file(cpu: usize, leaf: &str) -> Result<Option<u32>> {
+    let path = format!("/sys/devices/system/cpu/cpu{}/{}", cpu, leaf);
+    match std::fs::read_to_string(&path) {
+        Ok(val) => val
+            .trim()
+            .parse::<u32>()
+            .with_context(|| format!("Failed to parse {:?}'s content {:?}", &path, &val))
+            .map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to open {:?}", &path)),
+    }
+}
+
+// Whether a CPU is currently online, per
+// /sys/devices/system/cpu/cpuX/online. CPU0 (and, on some arches, every
+// CPU when hotplug is compiled out) doesn't expose this file at all and is
+// always online in that case.
+fn read_cpu_online(cpu: usize) -> Result<bool> {
+    let path = format!("/sys/devices/system/cpu/cpu{}/online", cpu);
+    match std::fs::read_to_string(&path) {
+        Ok(val) => Ok(val.trim() == "1"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e).with_context(|| format!("Failed to open {:?}", &path)),
+    }
+}
+
+// Read every CPU's capacity, preferring the arch-scaled 0-1024
+// cpu_capacity value and falling back, CPU by CPU, to cpufreq's max
+// frequency for whichever CPUs don't expose it (e.g. a big.LITTLE board
+// without arch-topology capacities wired up). cpuinfo_max_freq is in KHz
+// rather than the 0-1024 scale, so it's normalized against the highest
+// max frequency seen among the CPUs that needed the fallback. CPUs with
+// neither file default to CPU_CAPACITY_MAX, same as a fully symmetric
+// system.
+fn read_cpu_capacities(nr_cpus: usize) -> Result<Vec<u32>> {
+    let mut capacity = vec![CPU_CAPACITY_MAX; nr_cpus];
+    let mut freq_fallback = vec![None; nr_cpus];
+
+    for cpu in 0..nr_cpus {
+        match read_cpu_capacity_file(cpu, "cpu_capacity")? {
+            Some(val) => capacity[cpu] = val,
+            None => {
+                freq_fallback[cpu] = read_cpu_capacity_file(cpu, "cpufreq/cpuinfo_max_freq")?;
+            }
+        }
+    }
+
+    let max_freq = freq_fallback.iter().filter_map(|f| *f).max().unwrap_or(0);
+    if max_freq > 0 {
+        for (cpu, freq) in freq_fallback.iter().enumerate() {
+            if let Some(freq) = freq {
+                capacity[cpu] = ((*freq as u64 * CPU_CAPACITY_MAX as u64) / max_freq as u64) as u32;
+            }
+        }
+    }
+
+    Ok(capacity)
+}
+
+// Distance (in the kernel's ACPI SLIT units, where 10 is "local") between
+// two domains that live on the same NUMA node, used whenever the node
+// distance matrix can't be read, e.g. single-node machines.
+const SAME_NODE_DISTANCE: u32 = 10;
+
+// Migration cost tier between a pair of domains, derived from the
+// NUMA-distance data Topology already tracks and ordered from cheapest to
+// costliest so `Ord` gives the escalation order: try to resolve imbalance
+// within Llc before spilling over to Numa, and only reach for System once
+// both of those are exhausted. This is narrower than a real sched-domain
+// hierarchy: it's a cost multiplier over the existing flat domain/distance
+// data, not a separate SMT/LLC/NUMA/system tree with its own per-level
+// cpumasks, and domains themselves (built by cache_level or cpumasks) are
+// still the only unit pick_victim operates on — there's no SMT-sibling
+// level below a domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum BalanceLevel {
+    Llc,
+    Numa,
+    System,
+}
+
+impl BalanceLevel {
+    // How much more imbalance-reduction a migration at this level must
+    // show before it's worth paying the cost of crossing it, relative to
+    // an Llc-local move. A cross-NUMA or system-wide migration has to
+    // clear a proportionally higher bar than shuffling within the same
+    // cache domain.
+    fn migration_cost(&self) -> f64 {
+        match self {
+            BalanceLevel::Llc => 1.0,
+            BalanceLevel::Numa => 2.0,
+            BalanceLevel::System => 4.0,
+        }
+    }
+}
+
+// Read the CPU -> NUMA node mapping and the node x node SLIT distance
+// matrix from sysfs. Falls back to a single node of SAME_NODE_DISTANCE
+// when /sys/devices/system/node isn't populated, as is the case on
+// non-NUMA machines.
+fn read_numa_topology(nr_cpus: usize) -> Result<(Vec<usize>, Vec<Vec<u32>>)> {
+    let mut node_ids = BTreeSet::<usize>::new();
+    let node_dir = std::fs::read_dir("/sys/devices/system/node");
+    if node_dir.is_err() {
+        return Ok((vec![0; nr_cpus], vec![vec![SAME_NODE_DISTANCE]]));
+    }
+
+    for ent in node_dir.unwrap() {
+        let name = ent?.file_name();
+        let name = name.to_str().ok_or(anyhow!("invalid node dirent"))?;
+        if let Some(id) = name.strip_prefix("node") {
+            if let Ok(id) = id.parse::<usize>() {
+                node_ids.insert(id);
+            }
+        }
+    }
+
+    if node_ids.is_empty() {
+        return Ok((vec![0; nr_cpus], vec![vec![SAME_NODE_DISTANCE]]));
+    }
+
+    let mut cpu_node = vec![0; nr_cpus];
+    for &node in &node_ids {
+        let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+        let cpulist = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to open {:?}", &path))?;
+        for range in cpulist.trim().split(',').filter(|s| !s.is_empty()) {
+            let mut bounds = range.split('-');
+            let lo = bounds.next().unwrap().parse::<usize>()?;
+            let hi = match bounds.next() {
+                Some(hi) => hi.parse::<usize>()?,
+                None => lo,
+            };
+            for cpu in lo..=hi {
+                if cpu < nr_cpus {
+                    cpu_node[cpu] = node;
+                }
+            }
+        }
+    }
+
+    // Node IDs are assumed to be a contiguous 0..nr_nodes range, which is
+    // the case in practice, so the distance file's Nth entry lines up with
+    // node N.
+    let nr_nodes = node_ids.len();
+    let mut node_distance = vec![vec![SAME_NODE_DISTANCE; nr_nodes]; nr_nodes];
+    for &node in &node_ids {
+        let path = format!("/sys/devices/system/node/node{}/distance", node);
+        let distance = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to open {:?}", &path))?;
+        for (other, val) in distance.trim().split_whitespace().enumerate() {
+            if other < nr_nodes {
+                node_distance[node][other] = val
+                    .parse::<u32>()
+                    .with_context(|| format!("Failed to parse {:?}'s content {:?}", &path, val))?;
+            }
+        }
+    }
+
+    Ok((cpu_node, node_distance))
+}
+
 #[derive(Debug)]
 struct Topology {
     nr_cpus: usize,
     nr_doms: usize,
     dom_cpus: Vec<BitVec<u64, Lsb0>>,
     cpu_dom: Vec<Option<usize>>,
+    cpu_capacity: Vec<u32>,
+    dom_capacity: Vec<u64>,
+    dom_distance: Vec<Vec<u32>>,
 }
 
 impl Topology {
+    fn calc_dom_capacity(dom_cpus: &[BitVec<u64, Lsb0>], cpu_capacity: &[u32]) -> Vec<u64> {
+        dom_cpus
+            .iter()
+            .map(|cpus| {
+                cpus.iter_ones()
+                    .filter(|&cpu| cpu < cpu_capacity.len())
+                    .map(|cpu| cpu_capacity[cpu] as u64)
+                    .sum()
+            })
+            .collect()
+    }
+
+    // Domains are assumed not to straddle NUMA nodes, so the node of a
+    // domain's first CPU is representative of the whole domain.
+    fn calc_dom_distance(
+        dom_cpus: &[BitVec<u64, Lsb0>],
+        cpu_node: &[usize],
+        node_distance: &[Vec<u32>],
+    ) -> Vec<Vec<u32>> {
+        let dom_node: Vec<usize> = dom_cpus
+            .iter()
+            .map(|cpus| cpus.iter_ones().next().map(|cpu| cpu_node[cpu]).unwrap_or(0))
+            .collect();
+
+        dom_node
+            .iter()
+            .map(|&from| {
+                dom_node
+                    .iter()
+                    .map(|&to| node_distance[from][to])
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Distance, in SLIT units, at and below which two domains are
+    // considered to share an LLC tier rather than being a NUMA hop apart.
+    // Domains on the local node (distance == SAME_NODE_DISTANCE) fall
+    // here. Note there's no separate SMT tier below this: a domain is
+    // already the cache/cpumask grouping CPUs are assigned to, and this
+    // code doesn't build a finer per-core/per-thread level underneath it.
+    const LLC_DISTANCE: u32 = SAME_NODE_DISTANCE;
+
+    // Distance beyond which two domains are considered far enough apart
+    // (e.g. a multi-hop or cross-socket NUMA distance) to warrant treating
+    // the migration as system-wide rather than a single NUMA hop.
+    const SYSTEM_DISTANCE: u32 = SAME_NODE_DISTANCE * 2;
+
+    // Classify how topologically far apart two domains are, into the cost
+    // tiers above. This escalates the migration-cost bar with distance,
+    // which is a smaller piece of "sched-domain-style hierarchical
+    // balancing" than a real multi-level SMT/LLC/NUMA/system tree: it
+    // reuses the existing flat domain/distance data rather than
+    // introducing new per-level cpumask groupings or an SMT level.
+    fn balance_level(&self, dom_a: usize, dom_b: usize) -> BalanceLevel {
+        let distance = self.dom_distance[dom_a][dom_b];
+        if distance <= Self::LLC_DISTANCE {
+            BalanceLevel::Llc
+        } else if distance <= Self::SYSTEM_DISTANCE {
+            BalanceLevel::Numa
+        } else {
+            BalanceLevel::System
+        }
+    }
+
     fn from_cpumasks(cpumasks: &[String], nr_cpus: usize) -> Result<Self> {
         if cpumasks.len() > atropos_sys::MAX_DOMS as usize {
             bail!(
@@ -309,11 +808,19 @@ impl Topology {
             }
         }
 
+        let cpu_capacity = read_cpu_capacities(nr_cpus)?;
+        let dom_capacity = Self::calc_dom_capacity(&dom_cpus, &cpu_capacity);
+        let (cpu_node, node_distance) = read_numa_topology(nr_cpus)?;
+        let dom_distance = Self::calc_dom_distance(&dom_cpus, &cpu_node, &node_distance);
+
         Ok(Self {
             nr_cpus,
             nr_doms: dom_cpus.len(),
             dom_cpus,
             cpu_dom,
+            cpu_capacity,
+            dom_capacity,
+            dom_distance,
         })
     }
 
@@ -384,11 +891,19 @@ impl Topology {
             }
         }
 
+        let cpu_capacity = read_cpu_capacities(nr_cpus)?;
+        let dom_capacity = Self::calc_dom_capacity(&dom_cpus, &cpu_capacity);
+        let (cpu_node, node_distance) = read_numa_topology(nr_cpus)?;
+        let dom_distance = Self::calc_dom_distance(&dom_cpus, &cpu_node, &node_distance);
+
         Ok(Self {
             nr_cpus,
             nr_doms: dom_cpus.len(),
             dom_cpus,
             cpu_dom,
+            cpu_capacity,
+            dom_capacity,
+            dom_distance,
         })
     }
 }
@@ -399,21 +914,98 @@ struct Tuner {
     kick_greedy_under: f64,
     prev_cpu_stats: BTreeMap<usize, MyCpuStat>,
     dom_utils: Vec<f64>,
+    // Per-CPU online state, re-polled every tune_interval so a domain
+    // whose CPUs all went offline can be noticed and drained without
+    // restarting the scheduler.
+    online: Vec<bool>,
+    dom_online_cpus: Vec<usize>,
+    // Sum of cpu_capacity over @dom's currently-online CPUs, as opposed to
+    // Topology::dom_capacity which is fixed at startup over every CPU the
+    // domain was ever assigned. A domain that's lost some, but not all, of
+    // its CPUs to hotplug has a smaller slice of this than its nameplate
+    // capacity, and normalizing load against the stale full figure would
+    // understate how loaded what's left actually is.
+    dom_online_capacity: Vec<u64>,
+
+    energy_aware: bool,
+    energy_util_cap: f64,
+    energy_aware_enter_under: f64,
+    energy_aware_exit_over: f64,
+    consolidated: bool,
 }
 
 impl Tuner {
     fn new(top: Arc<Topology>, opts: &Opts) -> Result<Self> {
+        let online = (0..top.nr_cpus)
+            .map(read_cpu_online)
+            .collect::<Result<Vec<_>>>()?;
+        let dom_online_cpus = Self::calc_dom_online_cpus(&top, &online);
+        let dom_online_capacity = Self::calc_dom_online_capacity(&top, &online);
+
         Ok(Self {
             direct_greedy_under: opts.direct_greedy_under / 100.0,
             kick_greedy_under: opts.kick_greedy_under / 100.0,
             prev_cpu_stats: MyProcStat::read()?.cpus,
             dom_utils: vec![0.0; top.nr_doms],
+            online,
+            dom_online_cpus,
+            dom_online_capacity,
+
+            energy_aware: opts.energy_aware,
+            energy_util_cap: opts.energy_util_cap,
+            energy_aware_enter_under: opts.energy_aware_enter_under,
+            energy_aware_exit_over: opts.energy_aware_exit_over,
+            consolidated: false,
+
             top,
         })
     }
 
+    fn calc_dom_online_cpus(top: &Topology, online: &[bool]) -> Vec<usize> {
+        let mut dom_online_cpus = vec![0; top.nr_doms];
+        for cpu in 0..top.nr_cpus {
+            if online[cpu] {
+                if let Some(dom) = top.cpu_dom[cpu] {
+                    dom_online_cpus[dom] += 1;
+                }
+            }
+        }
+        dom_online_cpus
+    }
+
+    fn calc_dom_online_capacity(top: &Topology, online: &[bool]) -> Vec<u64> {
+        let mut dom_online_capacity = vec![0u64; top.nr_doms];
+        for cpu in 0..top.nr_cpus {
+            if online[cpu] {
+                if let Some(dom) = top.cpu_dom[cpu] {
+                    dom_online_capacity[dom] += top.cpu_capacity[cpu] as u64;
+                }
+            }
+        }
+        dom_online_capacity
+    }
+
+    // Whether @dom has any online CPU left to run tasks on. A domain whose
+    // CPUs all went offline should be drained rather than considered as a
+    // push or pull target.
+    fn dom_is_online(&self, dom: usize) -> bool {
+        self.dom_online_cpus[dom] > 0
+    }
+
+    // Whether the load balancer should be consolidating load onto a subset
+    // of domains rather than equalizing it across all of them.
+    fn consolidating(&self) -> bool {
+        self.energy_aware && self.consolidated
+    }
+
     fn step(&mut self, skel: &mut AtroposSkel) -> Result<()> {
         let curr_cpu_stats = MyProcStat::read()?.cpus;
+        self.online = (0..self.top.nr_cpus)
+            .map(read_cpu_online)
+            .collect::<Result<Vec<_>>>()?;
+        self.dom_online_cpus = Self::calc_dom_online_cpus(&self.top, &self.online);
+        self.dom_online_capacity = Self::calc_dom_online_capacity(&self.top, &self.online);
+
         let ti = &mut skel.bss().tune_input;
         let mut dom_nr_cpus = vec![0; self.top.nr_doms];
         let mut dom_util_sum = vec![0.0; self.top.nr_doms];
@@ -421,17 +1013,38 @@ impl Tuner {
         for cpu in 0..self.top.nr_cpus {
             // None domain indicates the CPU was offline during
             // initialization and None MyCpuStat indicates the CPU has gone
-            // down since then. Ignore both.
+            // down since then. Skip both, as well as CPUs that are
+            // currently offline per /sys.
             if let (Some(dom), Some(curr), Some(prev)) = (
                 self.top.cpu_dom[cpu],
                 curr_cpu_stats.get(&cpu),
                 self.prev_cpu_stats.get(&cpu),
             ) {
+                if !self.online[cpu] {
+                    continue;
+                }
                 dom_nr_cpus[dom] += 1;
                 dom_util_sum[dom] += curr.calc_util(prev);
             }
         }
 
+        if self.energy_aware {
+            let total_nr_cpus: usize = dom_nr_cpus.iter().sum();
+            let aggregate_util = if total_nr_cpus > 0 {
+                dom_util_sum.iter().sum::<f64>() / total_nr_cpus as f64
+            } else {
+                0.0
+            };
+
+            if self.consolidated {
+                if aggregate_util > self.energy_aware_exit_over {
+                    self.consolidated = false;
+                }
+            } else if aggregate_util < self.energy_aware_enter_under {
+                self.consolidated = true;
+            }
+        }
+
         for dom in 0..self.top.nr_doms {
             // Calculate the domain avg util. If there are no active CPUs,
             // it doesn't really matter. Go with 0.0 as that's less likely
@@ -478,6 +1091,20 @@ impl Tuner {
 struct TaskLoad {
     runnable_for: u64,
     load: f64,
+    // Raw PELT accumulator and how much of the in-flight ~1ms period it
+    // already accounts for, so the next sample can resume decaying from
+    // exactly where this one left off rather than re-deriving load from
+    // scratch each round.
+    load_sum: f64,
+    period_contrib_ns: u64,
+    // Monotonic timestamp this sample was taken at, so the next sample can
+    // decay by the time actually elapsed instead of assuming a fixed
+    // --interval, which jitters and can skip ticks entirely.
+    sampled_at: u64,
+    // lb_step generation this task was last migrated at, if ever, so a
+    // subsequent round can tell how many intervals ago that was and apply
+    // --migration-cost's protection window.
+    last_migrated_gen: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -486,14 +1113,33 @@ struct TaskInfo {
     dom_mask: u64,
     migrated: Cell<bool>,
     is_kworker: bool,
+    // Still within --migration-cost's post-migration protection window.
+    // Not excluded from being picked as a victim again, but pick_victim
+    // holds it to a stricter imbalance-improvement bar until it ages out.
+    migration_protected: bool,
 }
 
 struct LoadBalancer<'a, 'b, 'c> {
     maps: AtroposMapsMut<'a>,
     top: Arc<Topology>,
     task_loads: &'b mut BTreeMap<i32, TaskLoad>,
-    load_decay_factor: f64,
+    // y^n lookup table for PELT decay, shared across all tasks this round.
+    pelt_decay_table: [f64; PELT_DECAY_TABLE_LEN + 1],
     skip_kworkers: bool,
+    max_migration_distance: u32,
+    uclamp: Arc<UclampConfig>,
+    // Per-domain online CPU count, as of the last tune_interval poll. A
+    // domain with none left should be drained, not balanced into.
+    dom_online_cpus: Vec<usize>,
+    // Per-domain capacity summed over only the currently-online CPUs, as of
+    // the last tune_interval poll. Used in place of Topology::dom_capacity
+    // for every normalization below, since that figure is fixed at startup
+    // and doesn't shrink as a domain loses CPUs to hotplug.
+    dom_online_capacity: Vec<u64>,
+    migration_cost: f64,
+    // This round's lb_step generation, used to timestamp migrations and
+    // measure how many intervals old a task's last migration is.
+    lb_gen: u64,
 
     tasks_by_load: Vec<BTreeMap<OrderedFloat<f64>, TaskInfo>>,
     load_avg: f64,
@@ -502,6 +1148,9 @@ struct LoadBalancer<'a, 'b, 'c> {
     imbal: Vec<f64>,
     doms_to_push: BTreeMap<OrderedFloat<f64>, u32>,
     doms_to_pull: BTreeMap<OrderedFloat<f64>, u32>,
+    // Migrations executed this round, indexed by push_dom, for
+    // --migration-cost churn reporting.
+    dom_migrations: Vec<u64>,
 
     nr_lb_data_errors: &'c mut u64,
 }
@@ -529,15 +1178,26 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
         maps: AtroposMapsMut<'a>,
         top: Arc<Topology>,
         task_loads: &'b mut BTreeMap<i32, TaskLoad>,
-        load_decay_factor: f64,
         skip_kworkers: bool,
+        max_migration_distance: u32,
+        uclamp: Arc<UclampConfig>,
+        dom_online_cpus: Vec<usize>,
+        dom_online_capacity: Vec<u64>,
+        migration_cost: f64,
+        lb_gen: u64,
         nr_lb_data_errors: &'c mut u64,
     ) -> Self {
         Self {
             maps,
             task_loads,
-            load_decay_factor,
+            pelt_decay_table: pelt_decay_table(),
             skip_kworkers,
+            max_migration_distance,
+            uclamp,
+            dom_online_cpus,
+            dom_online_capacity,
+            migration_cost,
+            lb_gen,
 
             tasks_by_load: (0..top.nr_doms).map(|_| BTreeMap::<_, _>::new()).collect(),
             load_avg: 0f64,
@@ -546,6 +1206,7 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
             imbal: vec![0.0; top.nr_doms],
             doms_to_pull: BTreeMap::new(),
             doms_to_push: BTreeMap::new(),
+            dom_migrations: vec![0; top.nr_doms],
 
             nr_lb_data_errors,
 
@@ -553,6 +1214,10 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
         }
     }
 
+    fn dom_is_online(&self, dom: usize) -> bool {
+        self.dom_online_cpus[dom] > 0
+    }
+
     fn read_task_loads(&mut self, period: Duration) -> Result<()> {
         let now_mono = now_monotonic();
         let task_data = self.maps.task_data();
@@ -581,9 +1246,13 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
                     )
                 };
 
-                let (mut delta, prev_load) = match self.task_loads.get(&pid) {
-                    Some(prev) => (this_for - prev.runnable_for, Some(prev.load)),
-                    None => (this_for, None),
+                let (mut delta, prev_pelt, last_migrated_gen) = match self.task_loads.get(&pid) {
+                    Some(prev) => (
+                        this_for - prev.runnable_for,
+                        Some((prev.load_sum, prev.period_contrib_ns, prev.sampled_at)),
+                        prev.last_migrated_gen,
+                    ),
+                    None => (this_for, None, None),
                 };
 
                 // Non-zero this_at indicates that the task is currently
@@ -597,22 +1266,57 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
                 }
 
                 delta = delta.min(period.as_nanos() as u64);
-                let this_load = (weight as f64 * delta as f64 / period.as_nanos() as f64)
-                    .clamp(0.0, weight as f64);
 
-                let this_load = match prev_load {
-                    Some(prev_load) => {
-                        prev_load * self.load_decay_factor
-                            + this_load * (1.0 - self.load_decay_factor)
+                // Feed the task's runnable time since the last sample into
+                // its PELT accumulator rather than computing a flat
+                // fraction-of-period load: this decays each ~1ms period
+                // geometrically (y^32 ~= 0.5) instead of the whole sample
+                // window at once, so load responds to recent behavior
+                // without being as noisy as a single-period snapshot. A
+                // newly-seen task starts its accumulator from scratch.
+                let (prev_load_sum, prev_period_contrib_ns, dt) = match prev_pelt {
+                    Some((prev_load_sum, prev_period_contrib_ns, prev_sampled_at)) => {
+                        (prev_load_sum, prev_period_contrib_ns, now_mono.saturating_sub(prev_sampled_at))
                     }
-                    None => this_load,
+                    None => (0.0, 0, period.as_nanos() as u64),
                 };
+                let (task_load_sum, period_contrib_ns) = pelt_accumulate(
+                    prev_load_sum,
+                    prev_period_contrib_ns,
+                    dt,
+                    delta,
+                    &self.pelt_decay_table,
+                );
+
+                let load_avg = task_load_sum / PELT_LOAD_AVG_MAX;
+                let this_load = (load_avg * weight as f64).clamp(0.0, weight as f64);
+
+                // Apply the task's uclamp floor/ceiling so a boosted task
+                // (e.g. high util_min) is accounted as heavier than its
+                // actual runtime would suggest, making the balancer more
+                // reluctant to stack it with other work, and a capped task
+                // can't inflate a domain's load past its util_max. util_min/
+                // util_max are on the same [0, UCLAMP_MAX] scale the kernel
+                // uses, but this_load is weight-scaled (weight routinely
+                // exceeds UCLAMP_MAX for a non-default-niced task), so the
+                // clamp bounds are first scaled by weight: an unconfigured
+                // run (default_min=0, default_max=UCLAMP_MAX) is then a
+                // true no-op regardless of a task's weight.
+                let (util_min, util_max) = self.uclamp.lookup(pid);
+                let this_load = this_load.clamp(
+                    weight as f64 * util_min as f64 / UCLAMP_MAX as f64,
+                    weight as f64 * util_max as f64 / UCLAMP_MAX as f64,
+                );
 
                 this_task_loads.insert(
                     pid,
                     TaskLoad {
                         runnable_for: this_for,
                         load: this_load,
+                        load_sum: task_load_sum,
+                        period_contrib_ns,
+                        sampled_at: now_mono,
+                        last_migrated_gen,
                     },
                 );
 
@@ -622,6 +1326,14 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
                 if task_ctx.dom_mask == (1u64 << task_ctx.dom_id) {
                     continue;
                 }
+                // A task migrated within the last --migration-cost intervals
+                // is still in its protection window: pick_victim holds it to
+                // a stricter imbalance-improvement bar so it doesn't
+                // ping-pong back and forth across consecutive rounds as
+                // loads jitter.
+                let migration_protected = last_migrated_gen.map_or(false, |gen| {
+                    self.lb_gen.saturating_sub(gen) <= self.migration_cost.ceil() as u64
+                });
                 self.tasks_by_load[task_ctx.dom_id as usize].insert(
                     OrderedFloat(this_load),
                     TaskInfo {
@@ -629,21 +1341,51 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
                         dom_mask: task_ctx.dom_mask,
                         migrated: Cell::new(false),
                         is_kworker: task_ctx.is_kworker,
+                        migration_protected,
                     },
                 );
             }
         }
 
-        self.load_avg = load_sum / self.top.nr_doms as f64;
+        // Normalize by total domain capacity rather than plain domain count
+        // so that a big-core domain carrying proportionally more load isn't
+        // mistaken for being overloaded. Domains that have gone fully
+        // offline don't count towards the capacity average either, since
+        // there's nothing left there to balance towards.
+        let total_capacity: u64 = (0..self.top.nr_doms)
+            .filter(|&dom| self.dom_is_online(dom))
+            .map(|dom| self.dom_online_capacity[dom])
+            .sum();
+        self.load_avg = if total_capacity > 0 {
+            load_sum / total_capacity as f64
+        } else {
+            0.0
+        };
         *self.task_loads = this_task_loads;
         Ok(())
     }
 
-    // To balance dom loads we identify doms with lower and higher load than average
+    // To balance dom loads we identify doms with lower and higher load than
+    // average, comparing load normalized by each domain's capacity so that
+    // domains with more (or less) processing power are judged against the
+    // utilization they'd have if load were spread evenly.
     fn calculate_dom_load_balance(&mut self) -> Result<()> {
         for (dom, dom_load) in self.dom_loads.iter().enumerate() {
-            let imbal = dom_load - self.load_avg;
-            if imbal.abs() >= self.load_avg * Self::LOAD_IMBAL_HIGH_RATIO {
+            // A domain with no online CPUs left can't be balanced into, so
+            // just drain whatever load is still attributed to it instead
+            // of comparing it against the average.
+            if !self.dom_is_online(dom) {
+                if *dom_load > 0.0 {
+                    self.doms_to_push.insert(OrderedFloat(*dom_load), dom as u32);
+                    self.imbal[dom] = *dom_load;
+                }
+                continue;
+            }
+
+            let dom_capacity = self.dom_online_capacity[dom] as f64;
+            let norm_imbal = dom_load / dom_capacity - self.load_avg;
+            let imbal = norm_imbal * dom_capacity;
+            if norm_imbal.abs() >= self.load_avg * Self::LOAD_IMBAL_HIGH_RATIO {
                 if imbal > 0f64 {
                     self.doms_to_push.insert(OrderedFloat(imbal), dom as u32);
                 } else {
@@ -655,6 +1397,70 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
         Ok(())
     }
 
+    // Energy-aware counterpart to calculate_dom_load_balance(): instead of
+    // equalizing normalized load across every domain, consolidate it onto
+    // the more utilized half so the least utilized domains drain to zero
+    // and their CPUs can reach deeper idle states. Recipients are offered
+    // only the room below @energy_util_cap normalized utilization;
+    // pick_victim doesn't check that a migrated task fits in that room, so
+    // one task can take a recipient past the cap. The actual task
+    // selection and migration pairing (including the NUMA-distance
+    // preference) is unchanged; only which domains end up in
+    // doms_to_push/doms_to_pull and by how much differs. A push domain's
+    // full load is recorded as its imbalance here, but load_balance()'s
+    // push_max still caps how much of it actually moves in a single round
+    // (LOAD_IMBAL_PUSH_MAX_RATIO), so a domain drains to idle gradually
+    // over several rounds rather than all at once.
+    fn calculate_energy_aware_balance(&mut self, energy_util_cap: f64) -> Result<()> {
+        let nr_doms = self.top.nr_doms;
+        // A fully offline domain has no online capacity to divide by;
+        // treat it as maximally utilized so it sorts as a push (drain)
+        // candidate below rather than dividing by zero.
+        let util_of = |dom: usize| {
+            let capacity = self.dom_online_capacity[dom] as f64;
+            if capacity > 0.0 {
+                self.dom_loads[dom] / capacity
+            } else {
+                f64::INFINITY
+            }
+        };
+        let mut by_util: Vec<usize> = (0..nr_doms).collect();
+        by_util.sort_by(|&a, &b| util_of(a).partial_cmp(&util_of(b)).unwrap());
+
+        let nr_push = nr_doms / 2;
+        for (rank, &dom) in by_util.iter().enumerate() {
+            let load = self.dom_loads[dom];
+
+            // A fully offline domain is never a consolidation target; just
+            // drain whatever load is still attributed to it.
+            if !self.dom_is_online(dom) {
+                if load > 0.0 {
+                    self.doms_to_push.insert(OrderedFloat(load), dom as u32);
+                    self.imbal[dom] = load;
+                }
+                continue;
+            }
+
+            let capacity = self.dom_online_capacity[dom] as f64;
+            if load <= 0.0 {
+                continue;
+            }
+
+            if rank < nr_push {
+                self.doms_to_push.insert(OrderedFloat(load), dom as u32);
+                self.imbal[dom] = load;
+            } else {
+                let util = load / capacity;
+                if util < energy_util_cap {
+                    let room = capacity * energy_util_cap - load;
+                    self.doms_to_pull.insert(OrderedFloat(room), dom as u32);
+                    self.imbal[dom] = -room;
+                }
+            }
+        }
+        Ok(())
+    }
+
     // Find the first candidate pid which hasn't already been migrated and
     // can run in @pull_dom.
     fn find_first_candidate<'d, I>(
@@ -694,7 +1500,16 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
             to_pull
         );
 
-        let calc_new_imbal = |xfer: f64| (to_push - xfer).abs() + (to_pull - xfer).abs();
+        // @to_push and @to_pull are raw load deltas, but push_dom and
+        // pull_dom may have different capacities, so compare them as
+        // utilization (i.e. normalized by each domain's own capacity)
+        // rather than assuming a unit of load means the same thing on
+        // both sides of the transfer.
+        let push_capacity = self.dom_online_capacity[push_dom as usize] as f64;
+        let pull_capacity = self.dom_online_capacity[pull_dom as usize] as f64;
+        let calc_new_imbal = |xfer: f64| {
+            ((to_push - xfer) / push_capacity).abs() + ((to_pull - xfer) / pull_capacity).abs()
+        };
 
         trace!(
             "to_xfer={:.2} tasks_by_load={:?}",
@@ -738,15 +1553,29 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
             }
         };
 
-        // If the best candidate can't reduce the imbalance, there's nothing
-        // to do for this pair.
-        let old_imbal = to_push + to_pull;
-        if old_imbal < new_imbal {
+        // If the best candidate can't reduce the imbalance by enough to be
+        // worth the migration's cost, there's nothing to do for this pair.
+        // An Llc-local move just needs to help at all; a cross-NUMA or
+        // system-wide move needs to show a proportionally bigger payoff.
+        // --migration-cost raises the bar further still, but only for a
+        // candidate that's still within its own post-migration protection
+        // window: a task that's never (recently) moved shouldn't be held
+        // to a stricter bar than the per-BalanceLevel cost already sets.
+        let level = self.top.balance_level(push_dom as usize, pull_dom as usize);
+        let cost = level.migration_cost()
+            + if task.migration_protected {
+                self.migration_cost
+            } else {
+                0.0
+            };
+        let old_imbal = to_push / push_capacity + to_pull / pull_capacity;
+        if old_imbal < new_imbal * cost {
             trace!(
-                "skipping pid {}, dom {} -> {} won't improve imbal {:.2} -> {:.2}",
+                "skipping pid {}, dom {} -> {} ({:?}) won't improve imbal {:.2} -> {:.2}",
                 task.pid,
                 push_dom,
                 pull_dom,
+                level,
                 old_imbal,
                 new_imbal
             );
@@ -783,10 +1612,26 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
             loop {
                 let last_pushed = pushed;
 
-                // Pull from the most imbalaned to least.
+                // Pull from the most imbalaned to least, then stable-sort so
+                // that domains within max_migration_distance of push_dom are
+                // tried first; only once those are exhausted do we fall back
+                // to farther domains, keeping migrated tasks in the same
+                // NUMA neighborhood when possible. Within each of those two
+                // groups, cheaper BalanceLevels come first. pick_victim further gates
+                // farther candidates behind a bigger imbalance-improvement
+                // bar via BalanceLevel, so this ordering and that threshold
+                // work together to prefer the cheapest level that can
+                // absorb the imbalance.
                 let mut doms_to_pull = BTreeMap::<_, _>::new();
                 std::mem::swap(&mut self.doms_to_pull, &mut doms_to_pull);
                 let mut pull_doms = doms_to_pull.into_iter().rev().collect::<Vec<(_, _)>>();
+                pull_doms.sort_by_key(|(_, pull_dom)| {
+                    let distance = self.top.dom_distance[push_dom as usize][*pull_dom as usize];
+                    (
+                        distance > self.max_migration_distance,
+                        self.top.balance_level(push_dom as usize, *pull_dom as usize),
+                    )
+                });
 
                 for (to_pull, pull_dom) in pull_doms.iter_mut() {
                     if let Some((task, load)) =
@@ -800,6 +1645,15 @@ impl<'a, 'b, 'c> LoadBalancer<'a, 'b, 'c> {
 
                         // Ask BPF code to execute the migration.
                         let pid = task.pid;
+
+                        // Stamp this round's generation so a subsequent
+                        // round's read_task_loads() can tell the task is
+                        // still within --migration-cost's protection window.
+                        if let Some(task_load) = self.task_loads.get_mut(&pid) {
+                            task_load.last_migrated_gen = Some(self.lb_gen);
+                        }
+                        self.dom_migrations[push_dom as usize] += 1;
+
                         let cpid = (pid as libc::pid_t).to_ne_bytes();
                         if let Err(e) = self.maps.lb_data().update(
                             &cpid,
@@ -841,9 +1695,12 @@ struct Scheduler<'a> {
 
     sched_interval: Duration,
     tune_interval: Duration,
-    load_decay_factor: f64,
     balance_load: bool,
     balanced_kworkers: bool,
+    max_migration_distance: u32,
+    uclamp: Arc<UclampConfig>,
+    energy_util_cap: f64,
+    migration_cost: f64,
 
     top: Arc<Topology>,
 
@@ -852,6 +1709,10 @@ struct Scheduler<'a> {
     task_loads: BTreeMap<i32, TaskLoad>,
 
     nr_lb_data_errors: u64,
+    // lb_step generation counter, incremented once per load-balance round
+    // and handed to LoadBalancer so it can timestamp and age out
+    // --migration-cost's per-task protection window.
+    lb_gen: u64,
 
     tuner: Tuner,
 }
@@ -879,6 +1740,15 @@ impl<'a> Scheduler<'a> {
             Topology::from_cache_level(opts.cache_level, nr_cpus)?
         });
 
+        // nr_doms/nr_cpus/cpu_dom_id_map/dom_cpumasks live in BPF rodata,
+        // which libbpf freezes once the program is loaded below, so the
+        // CPU -> domain assignment itself can't be updated if a CPU that
+        // was offline at startup comes online later. Tuner tracks online
+        // state at the tune_interval cadence instead and keeps the
+        // userspace load balancer from pulling load into a domain whose
+        // CPUs have all gone offline; picking up newly-onlined CPUs that weren't
+        // assigned a domain at startup would need this topology data to
+        // live in bss instead.
         skel.rodata().nr_doms = top.nr_doms as u32;
         skel.rodata().nr_cpus = top.nr_cpus as u32;
 
@@ -892,10 +1762,11 @@ impl<'a> Scheduler<'a> {
             let (left, _) = dom_cpumask_slice.split_at_mut(raw_cpus_slice.len());
             left.clone_from_slice(cpus.as_raw_slice());
             info!(
-                "DOM[{:02}] cpumask{} ({} cpus)",
+                "DOM[{:02}] cpumask{} ({} cpus, capacity={})",
                 dom,
                 &format_cpumask(dom_cpumask_slice, nr_cpus),
-                cpus.count_ones()
+                cpus.count_ones(),
+                top.dom_capacity[dom],
             );
         }
 
@@ -918,6 +1789,11 @@ impl<'a> Scheduler<'a> {
 
         // Other stuff.
         let prev_total_cpu = MyProcStat::read()?.total;
+        let uclamp = Arc::new(UclampConfig::new(
+            opts.uclamp_min,
+            opts.uclamp_max,
+            opts.uclamp_config.as_deref(),
+        )?);
 
         Ok(Self {
             skel,
@@ -925,9 +1801,12 @@ impl<'a> Scheduler<'a> {
 
             sched_interval: Duration::from_secs_f64(opts.interval),
             tune_interval: Duration::from_secs_f64(opts.tune_interval),
-            load_decay_factor: opts.load_decay_factor.clamp(0.0, 0.99),
             balance_load: !opts.no_load_balance,
             balanced_kworkers: opts.balanced_kworkers,
+            max_migration_distance: opts.max_migration_distance,
+            uclamp,
+            energy_util_cap: opts.energy_util_cap,
+            migration_cost: opts.migration_cost,
 
             top: top.clone(),
 
@@ -936,6 +1815,7 @@ impl<'a> Scheduler<'a> {
             task_loads: BTreeMap::new(),
 
             nr_lb_data_errors: 0,
+            lb_gen: 0,
 
             tuner: Tuner::new(top, opts)?,
         })
@@ -989,6 +1869,7 @@ impl<'a> Scheduler<'a> {
         load_avg: f64,
         dom_loads: &Vec<f64>,
         imbal: &Vec<f64>,
+        dom_migrations: &Vec<u64>,
     ) {
         let stat = |idx| stats[idx as usize];
         let total = stat(atropos_sys::stat_idx_ATROPOS_STAT_WAKE_SYNC)
@@ -1001,14 +1882,22 @@ impl<'a> Scheduler<'a> {
             + stat(atropos_sys::stat_idx_ATROPOS_STAT_DSQ_DISPATCH)
             + stat(atropos_sys::stat_idx_ATROPOS_STAT_GREEDY);
 
+        let nr_online: usize = self.tuner.online.iter().filter(|&&online| online).count();
         info!(
-            "cpu={:7.2} bal={} load_avg={:8.2} task_err={} lb_data_err={} proc={:?}ms",
+            "cpu={:7.2} bal={} load_avg={:8.2} task_err={} lb_data_err={} proc={:?}ms online={}/{}{}",
             cpu_busy * 100.0,
             stats[atropos_sys::stat_idx_ATROPOS_STAT_LOAD_BALANCE as usize],
             load_avg,
             stats[atropos_sys::stat_idx_ATROPOS_STAT_TASK_GET_ERR as usize],
             self.nr_lb_data_errors,
             processing_dur.as_millis(),
+            nr_online,
+            self.top.nr_cpus,
+            if self.tuner.consolidating() {
+                " energy_aware=consolidating"
+            } else {
+                ""
+            },
         );
 
         let stat_pct = |idx| stat(idx) as f64 / total as f64 * 100.0;
@@ -1048,16 +1937,28 @@ impl<'a> Scheduler<'a> {
         );
 
         for i in 0..self.top.nr_doms {
+            // Normalize by online capacity, not the nameplate
+            // Topology::dom_capacity, so a domain that's lost CPUs to
+            // hotplug is reported as more loaded rather than understated.
+            let online_capacity = self.tuner.dom_online_capacity[i] as f64;
+            let cap_util = if online_capacity > 0.0 {
+                dom_loads[i] / online_capacity * 100.0
+            } else {
+                0.0
+            };
             info!(
-                "DOM[{:02}] util={:6.2} load={:8.2} imbal={}",
+                "DOM[{:02}] util={:6.2} load={:8.2} cap_util={:6.2} online={:3} imbal={} migrations={}",
                 i,
                 self.tuner.dom_utils[i] * 100.0,
                 dom_loads[i],
+                cap_util,
+                self.tuner.dom_online_cpus[i],
                 if imbal[i] == 0.0 {
                     format!("{:9.2}", 0.0)
                 } else {
                     format!("{:+9.2}", imbal[i])
                 },
+                dom_migrations[i],
             );
         }
     }
@@ -1071,13 +1972,22 @@ impl<'a> Scheduler<'a> {
             self.skel.maps_mut(),
             self.top.clone(),
             &mut self.task_loads,
-            self.load_decay_factor,
             self.balanced_kworkers,
+            self.max_migration_distance,
+            self.uclamp.clone(),
+            self.tuner.dom_online_cpus.clone(),
+            self.tuner.dom_online_capacity.clone(),
+            self.migration_cost,
+            self.lb_gen,
             &mut self.nr_lb_data_errors,
         );
 
         lb.read_task_loads(started_at.duration_since(self.prev_at))?;
-        lb.calculate_dom_load_balance()?;
+        if self.tuner.consolidating() {
+            lb.calculate_energy_aware_balance(self.energy_util_cap)?;
+        } else {
+            lb.calculate_dom_load_balance()?;
+        }
 
         if self.balance_load {
             lb.load_balance()?;
@@ -1085,7 +1995,8 @@ impl<'a> Scheduler<'a> {
 
         // Extract fields needed for reporting and drop lb to release
         // mutable borrows.
-        let (load_avg, dom_loads, imbal) = (lb.load_avg, lb.dom_loads, lb.imbal);
+        let (load_avg, dom_loads, imbal, dom_migrations) =
+            (lb.load_avg, lb.dom_loads, lb.imbal, lb.dom_migrations);
 
         self.report(
             &bpf_stats,
@@ -1094,9 +2005,11 @@ impl<'a> Scheduler<'a> {
             load_avg,
             &dom_loads,
             &imbal,
+            &dom_migrations,
         );
 
         self.prev_at = started_at;
+        self.lb_gen += 1;
         Ok(())
     }
 